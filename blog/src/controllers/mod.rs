@@ -0,0 +1,4 @@
+pub mod articles;
+pub mod content;
+pub mod playground;
+pub mod rss;
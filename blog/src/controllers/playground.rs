@@ -0,0 +1,52 @@
+use rwf::http::Method;
+use rwf::prelude::*;
+
+use crate::interpreter;
+
+/// Lets a visitor paste a snippet of the toy language from the interpreter
+/// series and run it in the browser: `GET` shows the empty form, `POST`
+/// lexes/parses/evaluates the submitted source and re-renders the page
+/// with the token stream, the AST and the resulting value (or the
+/// caret-underlined diagnostic, on failure). A `POST` gets that same
+/// rendered page back, sent with Turbo's stream content type; nothing is
+/// sent over the mounted `TurboStream` route.
+#[derive(Default)]
+pub struct Playground;
+
+#[async_trait]
+impl Controller for Playground {
+    async fn handle(&self, request: &Request) -> Result<Response, Error> {
+        let source = request.parameter::<String>("source")?.unwrap_or_default();
+
+        let (tokens, ast, value, error) = if source.trim().is_empty() {
+            (String::new(), String::new(), String::new(), String::new())
+        } else {
+            match interpreter::run(&source) {
+                Ok(report) => (report.tokens, report.ast, report.value, String::new()),
+                Err(rendered) => (String::new(), String::new(), String::new(), rendered),
+            }
+        };
+
+        let context = context!(
+            "title" => "Playground | Lev's blog",
+            "source" => source,
+            "tokens" => tokens,
+            "ast" => ast,
+            "value" => value,
+            "error" => error,
+        );
+
+        let body = Template::load("templates/playground.html")?.render(&context)?;
+        let response = Response::new().html(body);
+
+        // A submit gets the same rendered page back, labelled with Turbo's
+        // stream content type.
+        let response = if request.method() == Method::Post {
+            response.header("Content-Type", "text/vnd.turbo-stream.html")
+        } else {
+            response
+        };
+
+        Ok(response)
+    }
+}
@@ -7,7 +7,13 @@ pub struct Rss;
 #[async_trait]
 impl Controller for Rss {
     async fn handle(&self, _request: &Request) -> Result<Response, Error> {
-        let entries = Articles::articles().await?;
+        let mut entries = Articles::articles().await?;
+
+        // An article whose date couldn't be parsed has no `pub_date` to put
+        // in its `<pubDate>`; push it to the end instead of leaving it
+        // wherever Articles::articles()'s path-based sort happened to land it.
+        entries.sort_by_key(|entry| entry.pub_date.is_none());
+
         let template = Template::load("templates/rss.xml")?;
         let ctx = context!(
           "articles" => entries,
@@ -16,6 +22,6 @@ impl Controller for Rss {
 
         Ok(Response::new()
             .html(template.render(&ctx)?)
-            .header("Content-Type", "text/xml"))
+            .header("Content-Type", "application/rss+xml"))
     }
 }
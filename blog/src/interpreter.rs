@@ -0,0 +1,1839 @@
+#![allow(unused_mut, dead_code, unused_variables)]
+//! The toy language from the "Building a language interpreter from scratch"
+//! series, embedded so the `/playground` route can lex, parse and evaluate
+//! whatever source a visitor submits. This is a separate copy of
+//! `code/interpreter` rather than a shared dependency: unlike that one,
+//! every evaluation here runs against untrusted input, so `run` enforces a
+//! step/time/call-depth budget that the standalone interpreter has no need
+//! for, and a string multiplication is capped before it allocates rather
+//! than after.
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// A half-open byte range into the source, used to underline the
+/// offending text in diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// Span covering both `self` and `other`, used when folding two
+    /// sub-expressions into a larger one.
+    fn to(&self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
+/// Tracks the running byte offset while the lexer scans the source, so
+/// every token can carry a `Span`.
+#[derive(Debug, Clone, Copy, Default)]
+struct Cursor {
+    offset: usize,
+}
+
+impl Cursor {
+    fn advance(&mut self, c: char) {
+        self.offset += c.len_utf8();
+    }
+}
+
+/// List of all available tokens in our language.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Number(i64),
+    Plus,
+    String(String),
+    Star,
+    Identifier(String),
+    Let,
+    Equals,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Semicolon,
+    Comma,
+    Fn,
+    Return,
+    If,
+    Else,
+    Lt,
+    Gt,
+    EqEq,
+    Arrow,
+    For,
+    Colon,
+    Pipe,
+    PipeColon,
+}
+
+/// A token together with the span of source it was lexed from.
+#[derive(Debug, Clone)]
+pub struct FullToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// An error produced anywhere in the lex/parse/evaluate pipeline, carrying
+/// the span of source responsible so it can be rendered with a caret
+/// underline instead of just a message.
+#[derive(Debug, Clone)]
+pub struct InterpError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl InterpError {
+    fn new(message: impl Into<String>, span: Span) -> InterpError {
+        InterpError {
+            message: message.into(),
+            span,
+        }
+    }
+
+    /// Render the error as the source line the span falls on, followed by a
+    /// `^^^` underline beneath the offending text, codespan/ariadne-style.
+    pub fn render(&self, source: &str) -> String {
+        let mut line_start = 0;
+
+        for line in source.lines() {
+            let line_end = line_start + line.len();
+
+            if self.span.start <= line_end {
+                let start = self.span.start.saturating_sub(line_start).min(line.len());
+                let end = self.span.end.saturating_sub(line_start).clamp(start, line.len());
+
+                return format!(
+                    "{}\n{}{} {}",
+                    line,
+                    " ".repeat(start),
+                    "^".repeat((end - start).max(1)),
+                    self.message,
+                );
+            }
+
+            // +1 skips the newline character `lines()` strips.
+            line_start = line_end + 1;
+        }
+
+        self.message.clone()
+    }
+}
+
+impl fmt::Display for InterpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+type InterpResult<T> = Result<T, InterpError>;
+
+pub struct Lexer<'a> {
+    // Source code.
+    source: &'a str,
+    // Resulting list of tokens.
+    tokens: Vec<FullToken>,
+    // Buffer for multi-character tokens.
+    buffer: String,
+    // Byte offset where the token currently in `buffer` started.
+    buffer_start: usize,
+    // Current scan position.
+    cursor: Cursor,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Lexer {
+        Lexer {
+            source,
+            tokens: Vec::new(),
+            buffer: String::new(),
+            buffer_start: 0,
+            cursor: Cursor::default(),
+        }
+    }
+
+    /// Extract tokens one character at a time.
+    pub fn tokens(&mut self) -> InterpResult<Vec<FullToken>> {
+        let mut chars = self.source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let start = self.cursor.offset;
+
+            match c {
+                // Whitespace, including newlines, just separates tokens.
+                c if c.is_whitespace() => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                }
+                '0'..='9' => {
+                    if self.buffer.is_empty() {
+                        self.buffer_start = start;
+                    }
+                    self.buffer.push(c);
+                    self.cursor.advance(c);
+                }
+                '+' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                    self.push(Token::Plus, Span::new(start, self.cursor.offset));
+                }
+
+                // Double quote indicating the start of a string.
+                '"' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                    let mut string = String::new();
+
+                    while let Some(c) = chars.next() {
+                        self.cursor.advance(c);
+                        match c {
+                            // Closing double quote ends the string.
+                            '"' => break,
+                            _ => string.push(c),
+                        }
+                    }
+
+                    self.push(Token::String(string), Span::new(start, self.cursor.offset));
+                },
+
+                '*' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                    self.push(Token::Star, Span::new(start, self.cursor.offset));
+                }
+                '=' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+
+                    // `==` is a distinct token from the assignment `=`,
+                    // so peek ahead before deciding which one this is.
+                    if chars.peek() == Some(&'=') {
+                        chars.next();
+                        self.cursor.advance('=');
+                        self.push(Token::EqEq, Span::new(start, self.cursor.offset));
+                    } else {
+                        self.push(Token::Equals, Span::new(start, self.cursor.offset));
+                    }
+                }
+                '<' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                    self.push(Token::Lt, Span::new(start, self.cursor.offset));
+                }
+                '>' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                    self.push(Token::Gt, Span::new(start, self.cursor.offset));
+                }
+                '-' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+
+                    // The only thing `-` spells in this language is the
+                    // `->` of a lambda; there's no subtraction or unary
+                    // minus to fall back to.
+                    if chars.peek() == Some(&'>') {
+                        chars.next();
+                        self.cursor.advance('>');
+                        self.push(Token::Arrow, Span::new(start, self.cursor.offset));
+                    } else {
+                        return Err(InterpError::new(
+                            "expected '>' after '-'",
+                            Span::new(start, self.cursor.offset),
+                        ));
+                    }
+                }
+                '(' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                    self.push(Token::LParen, Span::new(start, self.cursor.offset));
+                }
+                ')' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                    self.push(Token::RParen, Span::new(start, self.cursor.offset));
+                }
+                '{' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                    self.push(Token::LBrace, Span::new(start, self.cursor.offset));
+                }
+                '}' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                    self.push(Token::RBrace, Span::new(start, self.cursor.offset));
+                }
+                ';' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                    self.push(Token::Semicolon, Span::new(start, self.cursor.offset));
+                }
+                ',' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                    self.push(Token::Comma, Span::new(start, self.cursor.offset));
+                }
+                ':' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+                    self.push(Token::Colon, Span::new(start, self.cursor.offset));
+                }
+                '|' => {
+                    self.process_token()?;
+                    self.cursor.advance(c);
+
+                    // The only things `|` spells in this language are the
+                    // pipe operators `|>` (apply) and `|:` (map over a
+                    // List); there's no bitwise-or to fall back to.
+                    match chars.peek() {
+                        Some(&'>') => {
+                            chars.next();
+                            self.cursor.advance('>');
+                            self.push(Token::Pipe, Span::new(start, self.cursor.offset));
+                        }
+                        Some(&':') => {
+                            chars.next();
+                            self.cursor.advance(':');
+                            self.push(Token::PipeColon, Span::new(start, self.cursor.offset));
+                        }
+                        _ => {
+                            return Err(InterpError::new(
+                                "expected '>' or ':' after '|'",
+                                Span::new(start, self.cursor.offset),
+                            ));
+                        }
+                    }
+                }
+
+                // All unknown characters are buffered
+                // until a known token is seen; `process_token` then
+                // reports a run that starts with a digit but isn't a
+                // number, and makes anything else a keyword or identifier.
+                c => {
+                    if self.buffer.is_empty() {
+                        self.buffer_start = start;
+                    }
+                    self.buffer.push(c);
+                    self.cursor.advance(c);
+                }
+            }
+        }
+
+        self.process_token()?;
+
+        Ok(std::mem::take(&mut self.tokens))
+    }
+
+    fn push(&mut self, token: Token, span: Span) {
+        self.tokens.push(FullToken { token, span });
+    }
+
+    fn process_token(&mut self) -> InterpResult<()> {
+        // Empty buffer means there is nothing to do here.
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let span = Span::new(self.buffer_start, self.buffer_start + self.buffer.len());
+
+        // If the token is numeric, parse it as a number.
+        if let Ok(number) = self.buffer.as_str().parse() {
+            self.push(Token::Number(number), span);
+        } else if self.buffer.chars().next().map(|c| c.is_ascii_digit()).unwrap_or(false) {
+            // Started like a number but failed to parse as one.
+            let message = format!("invalid number literal '{}'", self.buffer);
+            self.buffer.clear();
+            return Err(InterpError::new(message, span));
+        } else {
+            match self.buffer.as_str() {
+                "let" => self.push(Token::Let, span),
+                "fn" => self.push(Token::Fn, span),
+                "return" => self.push(Token::Return, span),
+                "if" => self.push(Token::If, span),
+                "else" => self.push(Token::Else, span),
+                "for" => self.push(Token::For, span),
+                // Otherwise, the token is some sort of word,
+                // which makes it an identifier.
+                _ => self.push(Token::Identifier(self.buffer.clone()), span),
+            }
+        }
+
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+// Parser combinators
+//
+// Instead of each grammar construct hand-rolling its own peek/consume/match
+// control flow, the parser below is built out of a handful of small,
+// reusable pieces: a combinator is just a function from "what's left to
+// parse" to "what it produced, and what's left after that". Adding a new
+// piece of grammar is then a matter of composing existing combinators
+// rather than threading a new match arm through every parsing function.
+
+/// The not-yet-consumed tokens a combinator parses from. Keeps hold of the
+/// full token list (not just what's left) so an "end of input" error can
+/// still point at the last real token instead of nowhere.
+#[derive(Debug, Clone, Copy)]
+struct TokenStream<'t> {
+    all: &'t [FullToken],
+    pos: usize,
+}
+
+impl<'t> TokenStream<'t> {
+    fn first(&self) -> Option<&'t FullToken> {
+        self.all.get(self.pos)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.all.len()
+    }
+
+    fn len(&self) -> usize {
+        self.all.len() - self.pos
+    }
+
+    /// The stream with its first `n` tokens consumed.
+    fn advance(&self, n: usize) -> TokenStream<'t> {
+        TokenStream { all: self.all, pos: self.pos + n }
+    }
+}
+
+impl<'t> From<&'t [FullToken]> for TokenStream<'t> {
+    fn from(all: &'t [FullToken]) -> TokenStream<'t> {
+        TokenStream { all, pos: 0 }
+    }
+}
+
+/// What every combinator produces: on success, the parsed value and the
+/// tokens left over; on failure, a span-tagged error.
+type PResult<'t, O> = InterpResult<(O, TokenStream<'t>)>;
+
+/// The span to blame when a combinator runs out of tokens: the end of the
+/// last real token, not whatever's left of `input` (which, by the time
+/// anything calls this, is always empty - that's what "ran out" means).
+fn eof_span(input: TokenStream) -> Span {
+    input
+        .all
+        .last()
+        .map(|t| Span::new(t.span.end, t.span.end))
+        .unwrap_or(Span::new(0, 0))
+}
+
+/// The span covering everything between `input` (before a combinator ran)
+/// and `rest` (what it left behind).
+fn consumed_span(input: TokenStream, rest: TokenStream) -> Span {
+    let consumed = &input.all[input.pos..rest.pos];
+    match (consumed.first(), consumed.last()) {
+        (Some(first), Some(last)) => first.span.to(last.span),
+        _ => eof_span(input),
+    }
+}
+
+/// Matches a single token equal to `want`.
+fn just<'t>(want: Token) -> impl Fn(TokenStream<'t>) -> PResult<'t, Span> {
+    move |input| match input.first() {
+        Some(full) if full.token == want => Ok((full.span, input.advance(1))),
+        Some(full) => Err(InterpError::new(
+            format!("expected {:?}, got: {:?}", want, full.token),
+            full.span,
+        )),
+        None => Err(InterpError::new(
+            format!("expected {:?}, found end of input", want),
+            eof_span(input),
+        )),
+    }
+}
+
+/// Matches and extracts from a single token, for tokens (`Number`,
+/// `String`, `Identifier`) that carry data `just` can't compare against.
+fn filter<'t, O>(
+    expected: &'static str,
+    f: impl Fn(&Token) -> Option<O>,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, O> {
+    move |input| match input.first() {
+        Some(full) => match f(&full.token) {
+            Some(out) => Ok((out, input.advance(1))),
+            None => Err(InterpError::new(
+                format!("expected {}, got: {:?}", expected, full.token),
+                full.span,
+            )),
+        },
+        None => Err(InterpError::new(
+            format!("expected {}, found end of input", expected),
+            eof_span(input),
+        )),
+    }
+}
+
+/// Runs `a` then `b` in sequence, pairing up both outputs.
+fn then<'t, A, B>(
+    a: impl Fn(TokenStream<'t>) -> PResult<'t, A>,
+    b: impl Fn(TokenStream<'t>) -> PResult<'t, B>,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, (A, B)> {
+    move |input| {
+        let (a, rest) = a(input)?;
+        let (b, rest) = b(rest)?;
+        Ok(((a, b), rest))
+    }
+}
+
+/// Tries `a`; on failure falls back to `b` against the same input
+/// (combinators never consume anything on a failing run, so there's
+/// nothing to rewind).
+fn or<'t, O>(
+    a: impl Fn(TokenStream<'t>) -> PResult<'t, O>,
+    b: impl Fn(TokenStream<'t>) -> PResult<'t, O>,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, O> {
+    move |input| a(input).or_else(|_| b(input))
+}
+
+/// One or more `p`, separated by `sep`. `sep`'s own output is discarded.
+fn separated_by<'t, O, S>(
+    p: impl Fn(TokenStream<'t>) -> PResult<'t, O>,
+    sep: impl Fn(TokenStream<'t>) -> PResult<'t, S>,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, Vec<O>> {
+    move |input| {
+        let (first, mut rest) = p(input)?;
+        let mut out = vec![first];
+
+        while let Ok((_, after_sep)) = sep(rest) {
+            let (item, after_item) = p(after_sep)?;
+            out.push(item);
+            rest = after_item;
+        }
+
+        Ok((out, rest))
+    }
+}
+
+/// Transforms a combinator's output, leaving the span/rest plumbing alone.
+fn map<'t, A, B>(
+    p: impl Fn(TokenStream<'t>) -> PResult<'t, A>,
+    f: impl Fn(A) -> B,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, B> {
+    move |input| {
+        let (a, rest) = p(input)?;
+        Ok((f(a), rest))
+    }
+}
+
+/// Like `map`, but `f` also receives the span of everything `p` consumed,
+/// for building AST nodes that carry their own span.
+fn map_with_span<'t, A, B>(
+    p: impl Fn(TokenStream<'t>) -> PResult<'t, A>,
+    f: impl Fn(A, Span) -> B,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, B> {
+    move |input| {
+        let (a, rest) = p(input)?;
+        Ok((f(a, consumed_span(input, rest)), rest))
+    }
+}
+
+/// `p`, preceded by `open` and followed by `close`, keeping only `p`'s
+/// output. Built from `then`/`map` rather than its own primitive.
+fn delimited<'t, O>(
+    open: impl Fn(TokenStream<'t>) -> PResult<'t, Span>,
+    p: impl Fn(TokenStream<'t>) -> PResult<'t, O>,
+    close: impl Fn(TokenStream<'t>) -> PResult<'t, Span>,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, O> {
+    map(then(then(open, p), close), |((_, out), _)| out)
+}
+
+/// Always succeeds without consuming input, producing a clone of `value`.
+/// Used to give a `separated_by`-style list an empty-list fallback.
+fn succeed<'t, O: Clone>(value: O) -> impl Fn(TokenStream<'t>) -> PResult<'t, O> {
+    move |input| Ok((value.clone(), input))
+}
+
+/// Zero or more `p`, separated by `sep` (unlike `separated_by`, which
+/// requires at least one match). Used for argument/parameter lists, which
+/// may be empty.
+fn separated_by0<'t, O: Clone, S>(
+    p: impl Fn(TokenStream<'t>) -> PResult<'t, O>,
+    sep: impl Fn(TokenStream<'t>) -> PResult<'t, S>,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, Vec<O>> {
+    or(separated_by(p, sep), succeed(Vec::new()))
+}
+
+/// Builds a left-associative binary-operator tier: `next` parses one
+/// operand at the tier below, and any number of `op next` pairs fold onto
+/// it left-to-right. Adding a new precedence tier (e.g. `^`) is just one
+/// more call to this, one level up.
+///
+/// The loop is written out by hand rather than collecting `op next` pairs
+/// with a generic zero-or-more combinator: such a combinator treats any
+/// failure of its inner parser as "no more matches", which is right for
+/// genuinely optional repetition but wrong here. Once an operator token has
+/// been seen, the right-hand operand is mandatory - `1 +` should fail with "expected an expression"
+/// right after the `+`, not have its `+` silently left unconsumed.
+fn binary_level<'t>(
+    next: impl Fn(TokenStream<'t>) -> PResult<'t, Expression>,
+    ops: &'static [Operation],
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, Expression> {
+    move |input| {
+        let (mut left, mut rest) = next(input)?;
+
+        while let Some(op) = rest
+            .first()
+            .and_then(|t| Operation::from_token(&t.token))
+            .filter(|op| ops.contains(op))
+        {
+            let (right, after_right) = next(rest.advance(1))?;
+            let span = left.span().to(right.span());
+
+            left = Expression::Binary { left: Box::new(left), op, right: Box::new(right), span };
+            rest = after_right;
+        }
+
+        Ok((left, rest))
+    }
+}
+
+/// An operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Addition,
+    Multiplication,
+    Equal,
+    LessThan,
+    GreaterThan,
+}
+
+impl Operation {
+    /// Map a token to the operation it represents, if it is an operator at all.
+    fn from_token(token: &Token) -> Option<Operation> {
+        match token {
+            Token::Plus => Some(Operation::Addition),
+            Token::Star => Some(Operation::Multiplication),
+            Token::EqEq => Some(Operation::Equal),
+            Token::Lt => Some(Operation::LessThan),
+            Token::Gt => Some(Operation::GreaterThan),
+            _ => None,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Operation::Addition => "+",
+            Operation::Multiplication => "*",
+            Operation::Equal => "==",
+            Operation::LessThan => "<",
+            Operation::GreaterThan => ">",
+        }
+    }
+}
+
+/// A constant value.
+#[derive(Debug, Clone)]
+enum Value {
+    Number(i64),
+    /// A value storing a string.
+    String(String),
+    /// The value of a block or program that didn't end on an expression,
+    /// e.g. one whose last statement is a `let` binding.
+    Unit,
+    /// A boolean, produced by a comparison and consumed by `if`.
+    Bool(bool),
+    /// A user-defined function, storing its own parameter list and body so
+    /// a call can bind arguments and evaluate the body against them, plus
+    /// the scope it closed over: a nested `(y) -> x + y` lambda still sees
+    /// the `x` its enclosing call bound, wherever the lambda ends up called
+    /// from.
+    Function {
+        params: Vec<String>,
+        body: Box<Expression>,
+        captured: Environment,
+    },
+
+    /// An ordered sequence of values, produced by `range`/`push` and
+    /// consumed by `for`.
+    List(Vec<Value>),
+
+    /// A native function implemented in Rust rather than interpreted
+    /// `Expression` statements, looked up by name in `call_builtin`.
+    /// Registered under its own name in `Environment::new` so it's callable
+    /// like any other function.
+    Builtin(&'static str),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "Number",
+            Value::String(_) => "String",
+            Value::Unit => "Unit",
+            Value::Function { .. } => "Function",
+            Value::Bool(_) => "Bool",
+            Value::List(_) => "List",
+            Value::Builtin(_) => "Builtin",
+        }
+    }
+
+    /// Evaluate a comparison operator, producing a `Value::Bool`.
+    fn compare(self, other: Value, op: Operation, span: Span) -> InterpResult<Value> {
+        if op == Operation::Equal {
+            let equal = match (&self, &other) {
+                (Value::Number(a), Value::Number(b)) => a == b,
+                (Value::String(a), Value::String(b)) => a == b,
+                (Value::Bool(a), Value::Bool(b)) => a == b,
+                (Value::Unit, Value::Unit) => true,
+                _ => false,
+            };
+
+            return Ok(Value::Bool(equal));
+        }
+
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(match op {
+                Operation::LessThan => a < b,
+                Operation::GreaterThan => a > b,
+                _ => unreachable!("only orderings reach here"),
+            })),
+
+            (Value::String(a), Value::String(b)) => Ok(Value::Bool(match op {
+                Operation::LessThan => a < b,
+                Operation::GreaterThan => a > b,
+                _ => unreachable!("only orderings reach here"),
+            })),
+
+            (a, b) => Err(InterpError::new(
+                format!(
+                    "'{}' between {} and {} not supported",
+                    op.symbol(), a.type_name(), b.type_name(),
+                ),
+                span,
+            )),
+        }
+    }
+
+    fn add(self, other: Value, span: Span) -> InterpResult<Value> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
+
+            // Supports 21 + "hello world"
+            (Value::Number(a), Value::String(s)) => Ok(Value::String(a.to_string() + &s)),
+
+            // Supports "hello world" + 21
+            (Value::String(s), Value::Number(a)) => Ok(Value::String(s + a.to_string().as_str())),
+
+            (a, b) => Err(InterpError::new(
+                format!(
+                    "'+' between {} and {} not supported",
+                    a.type_name(), b.type_name(),
+                ),
+                span,
+            )),
+        }
+    }
+
+    fn mul(self, other: Value, span: Span) -> InterpResult<Value> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a * b)),
+
+            // Supports 3 * "hello world". A string repeat allocates all at
+            // once, before the evaluator gets a chance to charge it against
+            // the step budget, so its result size is capped directly here -
+            // otherwise something like `"x" * 999999999` exhausts memory in
+            // a single step.
+            (Value::Number(a), Value::String(s)) => Self::repeat_string(s, a, span),
+
+            // Supports "hello world" * 3
+            (Value::String(s), Value::Number(a)) => Self::repeat_string(s, a, span),
+
+            (a, b) => Err(InterpError::new(
+                format!(
+                    "'*' between {} and {} not supported",
+                    a.type_name(), b.type_name(),
+                ),
+                span,
+            )),
+        }
+    }
+
+    /// Repeats `s` `count` times, rejecting the multiplication outright if
+    /// the result would exceed [`MAX_STRING_LEN`] rather than allocating it.
+    fn repeat_string(s: String, count: i64, span: Span) -> InterpResult<Value> {
+        let count = count.max(0) as usize;
+
+        if s.len().saturating_mul(count) > MAX_STRING_LEN {
+            return Err(InterpError::new("computation limit exceeded", span));
+        }
+
+        Ok(Value::String(s.repeat(count)))
+    }
+}
+
+/// The largest string a single evaluation is allowed to produce, so a
+/// string-repeat can't allocate its way past the step/time budget below.
+const MAX_STRING_LEN: usize = 1_000_000;
+
+/// Implements the builtins registered in `Environment::new`. `Environment`
+/// values are plain, deep-cloned data rather than shared/mutable cells, so
+/// `push` returns a new `List` instead of mutating its argument in place -
+/// the caller reassigns it, the same way `let xs = push(xs, 5);` works for
+/// any other value in this language. `range` is capped by the step budget
+/// the same as everything else, but charges it once per produced element
+/// rather than once for the call as a whole, since a single huge
+/// `range(n)` still allocates its whole list up front.
+fn call_builtin(name: &str, mut args: Vec<Value>, span: Span, budget: &Budget) -> InterpResult<Value> {
+    fn arity_error(name: &str, want: usize, got: usize, span: Span) -> InterpError {
+        InterpError::new(
+            format!("'{}' takes {} argument(s), got {}", name, want, got),
+            span,
+        )
+    }
+
+    match name {
+        "range" => {
+            if args.len() != 1 {
+                return Err(arity_error(name, 1, args.len(), span));
+            }
+
+            match args.remove(0) {
+                Value::Number(n) => {
+                    let mut out = Vec::new();
+                    for i in 0..n.max(0) {
+                        budget.tick(span)?;
+                        out.push(Value::Number(i));
+                    }
+                    Ok(Value::List(out))
+                }
+                other => Err(InterpError::new(
+                    format!("'range' expects a Number, got {}", other.type_name()),
+                    span,
+                )),
+            }
+        }
+
+        "len" => {
+            if args.len() != 1 {
+                return Err(arity_error(name, 1, args.len(), span));
+            }
+
+            match args.remove(0) {
+                Value::List(items) => Ok(Value::Number(items.len() as i64)),
+                Value::String(s) => Ok(Value::Number(s.len() as i64)),
+                other => Err(InterpError::new(
+                    format!("'len' expects a List or String, got {}", other.type_name()),
+                    span,
+                )),
+            }
+        }
+
+        "push" => {
+            if args.len() != 2 {
+                return Err(arity_error(name, 2, args.len(), span));
+            }
+
+            let item = args.remove(1);
+            match args.remove(0) {
+                Value::List(mut items) => {
+                    items.push(item);
+                    Ok(Value::List(items))
+                }
+                other => Err(InterpError::new(
+                    format!("'push' expects a List, got {}", other.type_name()),
+                    span,
+                )),
+            }
+        }
+
+        "map" => {
+            if args.len() != 2 {
+                return Err(arity_error(name, 2, args.len(), span));
+            }
+
+            let func = args.remove(1);
+            match args.remove(0) {
+                Value::List(items) => {
+                    let mut out = Vec::with_capacity(items.len());
+                    for item in items {
+                        out.push(call_value(func.clone(), vec![item], None, span, budget)?);
+                    }
+                    Ok(Value::List(out))
+                }
+                other => Err(InterpError::new(
+                    format!("'map' expects a List, got {}", other.type_name()),
+                    span,
+                )),
+            }
+        }
+
+        "filter" => {
+            if args.len() != 2 {
+                return Err(arity_error(name, 2, args.len(), span));
+            }
+
+            let predicate = args.remove(1);
+            match args.remove(0) {
+                Value::List(items) => {
+                    let mut out = Vec::new();
+                    for item in items {
+                        let keep = match call_value(predicate.clone(), vec![item.clone()], None, span, budget)? {
+                            Value::Bool(b) => b,
+                            other => {
+                                return Err(InterpError::new(
+                                    format!(
+                                        "'filter' predicate must return a Bool, got {}",
+                                        other.type_name(),
+                                    ),
+                                    span,
+                                ));
+                            }
+                        };
+
+                        if keep {
+                            out.push(item);
+                        }
+                    }
+                    Ok(Value::List(out))
+                }
+                other => Err(InterpError::new(
+                    format!("'filter' expects a List, got {}", other.type_name()),
+                    span,
+                )),
+            }
+        }
+
+        "foldl" => {
+            if args.len() != 3 {
+                return Err(arity_error(name, 3, args.len(), span));
+            }
+
+            let func = args.remove(2);
+            let init = args.remove(1);
+            match args.remove(0) {
+                Value::List(items) => {
+                    let mut acc = init;
+                    for item in items {
+                        acc = call_value(func.clone(), vec![acc, item], None, span, budget)?;
+                    }
+                    Ok(acc)
+                }
+                other => Err(InterpError::new(
+                    format!("'foldl' expects a List, got {}", other.type_name()),
+                    span,
+                )),
+            }
+        }
+
+        _ => unreachable!("builtin '{}' registered but not implemented", name),
+    }
+}
+
+/// Invoke an already-evaluated function value - a `Value::Function` or a
+/// `Value::Builtin` - with already-evaluated arguments. Shared by
+/// `Expression::Call`, the pipe operators, and the higher-order builtins
+/// (`map`/`filter`/`foldl`), all of which end up in "I have a function
+/// value and some arguments, call it" once they've settled what the
+/// function is. `name` is the identifier the callee was looked up under,
+/// when there is one. It makes arity/type errors read like
+/// `'add' takes 2 argument(s), got 1` instead of a generic message, and a
+/// user function sees itself bound under it, so its body can call itself;
+/// a callee that isn't a bare name (a lambda literal, a pipe's right side,
+/// a builtin's function argument) just falls back to "function"/"value".
+///
+/// `budget` is threaded through every path that can recurse back into
+/// `Expression::evaluate` (a user function's body, or a builtin like `map`
+/// calling back into a user-supplied function) so the step/time/call-depth
+/// caps apply uniformly, not just to direct `name(args)` calls.
+fn call_value(
+    function: Value,
+    args: Vec<Value>,
+    name: Option<&str>,
+    span: Span,
+    budget: &Budget,
+) -> InterpResult<Value> {
+    match function {
+        Value::Builtin(builtin_name) => call_builtin(builtin_name, args, span, budget),
+
+        Value::Function { params, body, captured } => {
+            if params.len() != args.len() {
+                return Err(InterpError::new(
+                    format!(
+                        "'{}' takes {} argument(s), got {}",
+                        name.unwrap_or("function"), params.len(), args.len(),
+                    ),
+                    span,
+                ));
+            }
+
+            // Layered on the scope the function closed over, not the call
+            // site's, so a lambda returned out of another function still
+            // sees the variables it was defined alongside.
+            let mut call_env = Environment::child_of(&captured);
+
+            // The captured scope of a `fn` definition predates its own
+            // closure, so bind the name it was called by to the function
+            // itself; parameters are bound after it, so they shadow it.
+            if let Some(name) = name {
+                call_env.set(name, Value::Function {
+                    params: params.clone(),
+                    body: body.clone(),
+                    captured: captured.clone(),
+                });
+            }
+
+            for (param, value) in params.iter().zip(args) {
+                call_env.set(param, value);
+            }
+
+            // Recursion grows the real Rust call stack, which a step count
+            // alone doesn't bound - something like `fn f(n) { f(n) }` would
+            // otherwise overflow the stack long before exhausting the step
+            // budget. `enter_call` caps how deep that can go.
+            budget.enter_call(span)?;
+
+            // The function's own `return`/trailing value ends here:
+            // whichever it was, the call itself always completes normally.
+            let result = body.evaluate(&call_env, budget)?.into_value();
+
+            budget.exit_call();
+
+            Ok(result)
+        }
+
+        other => {
+            let message = match name {
+                Some(name) => format!("'{}' is a {}, not a function", name, other.type_name()),
+                None => format!("cannot call a {} value", other.type_name()),
+            };
+            Err(InterpError::new(message, span))
+        }
+    }
+}
+
+/// Expression term.
+#[derive(Debug, Clone)]
+enum Term {
+    /// Constant value.
+    Value(Value),
+
+    /// Variable value.
+    Variable {
+        /// Name of the variable.
+        name: String,
+    }
+}
+
+impl Term {
+    /// Evaluate the term given the environment.
+    pub fn evaluate(&self, env: &Environment, span: Span) -> InterpResult<Value> {
+        match self {
+            Term::Value(value) => Ok(value.clone()),
+            Term::Variable { name } => {
+                env.get(name).ok_or_else(|| {
+                    InterpError::new(format!("variable '{}' not found", name), span)
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expression {
+    /// A binary operation. Both sides are full expressions so operators
+    /// can be chained arbitrarily deep, e.g. `2 + 3 * 4 + 5`.
+    Binary {
+        left: Box<Expression>,
+        op: Operation,
+        right: Box<Expression>,
+        span: Span,
+    },
+
+    /// Just a term by itself.
+    Term(Term, Span),
+
+    /// `{ statements... }`. Introduces a child scope, runs each statement in
+    /// it, and evaluates to whatever the last one evaluates to.
+    Block {
+        statements: Vec<Statement>,
+        span: Span,
+    },
+
+    /// `callee(args...)`. `callee` is evaluated like any other expression
+    /// and must produce a `Value::Function` or `Value::Builtin`; this is
+    /// what lets `((x) -> x + 1)(5)` and `make_adder(5)(3)` call straight
+    /// through a lambda literal or a call's own result, not just a bare
+    /// name.
+    Call {
+        callee: Box<Expression>,
+        args: Vec<Expression>,
+        span: Span,
+    },
+
+    /// `if condition { ... } else { ... }`. Expression-oriented: evaluates
+    /// to whichever branch was taken, or `Value::Unit` if the condition was
+    /// false and there's no `else`.
+    If {
+        condition: Box<Expression>,
+        then_branch: Box<Expression>,
+        else_branch: Option<Box<Expression>>,
+        span: Span,
+    },
+
+    /// `(params) -> body`. An anonymous function value, evaluating to a
+    /// `Value::Function` that closes over whatever environment it's
+    /// evaluated in.
+    Lambda {
+        params: Vec<String>,
+        body: Box<Expression>,
+        span: Span,
+    },
+
+    /// `value |> func`. Sugar for calling `func` with `value` as its only
+    /// argument, read left-to-right through a pipeline.
+    Pipe {
+        value: Box<Expression>,
+        func: Box<Expression>,
+        span: Span,
+    },
+
+    /// `value |: func`. `value` must evaluate to a `Value::List`; applies
+    /// `func` to each element and collects the results into a new List.
+    PipeMap {
+        value: Box<Expression>,
+        func: Box<Expression>,
+        span: Span,
+    },
+}
+
+impl Expression {
+    fn span(&self) -> Span {
+        match self {
+            Expression::Binary { span, .. } => *span,
+            Expression::Term(_, span) => *span,
+            Expression::Block { span, .. } => *span,
+            Expression::Call { span, .. } => *span,
+            Expression::If { span, .. } => *span,
+            Expression::Lambda { span, .. } => *span,
+            Expression::Pipe { span, .. } => *span,
+            Expression::PipeMap { span, .. } => *span,
+        }
+    }
+
+    /// Parse a single expression: the pipe operators wrapped around the
+    /// three precedence tiers below (comparisons loosest, then addition,
+    /// then multiplication), each folding same-tier operators
+    /// left-associative. Doesn't itself check for end-of-input; callers
+    /// decide where an expression is supposed to stop (a `;`, a `)`, a `}`,
+    /// or EOF).
+    pub fn parse<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        Self::pipe_expr(input)
+    }
+
+    /// The pipe operators (`|>` apply, `|:` map-over-List), the
+    /// loosest-binding tier of all - `a + b |> f` parses as `(a + b) |> f`.
+    /// Doesn't go through `binary_level`: the pipes aren't `Operation`s
+    /// (they don't flow through `Value::add`/`compare`/etc.), they desugar
+    /// to a function call instead.
+    fn pipe_expr<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        let (mut left, mut rest) = Self::cmp_expr(input)?;
+
+        loop {
+            match rest.first() {
+                Some(FullToken { token: Token::Pipe, .. }) => {
+                    let (_, after_op) = just(Token::Pipe)(rest)?;
+                    let (func, after_func) = Self::cmp_expr(after_op)?;
+                    let span = left.span().to(func.span());
+                    left = Expression::Pipe { value: Box::new(left), func: Box::new(func), span };
+                    rest = after_func;
+                }
+                Some(FullToken { token: Token::PipeColon, .. }) => {
+                    let (_, after_op) = just(Token::PipeColon)(rest)?;
+                    let (func, after_func) = Self::cmp_expr(after_op)?;
+                    let span = left.span().to(func.span());
+                    left = Expression::PipeMap { value: Box::new(left), func: Box::new(func), span };
+                    rest = after_func;
+                }
+                _ => break,
+            }
+        }
+
+        Ok((left, rest))
+    }
+
+    /// Comparisons (`==`, `<`, `>`), the loosest-binding tier below the
+    /// pipes. Like addition and multiplication below, repeated comparisons
+    /// fold left-associative, so `a < b < c` parses as `(a < b) < c` -
+    /// which then fails at evaluation once the left side turns into a
+    /// `Bool`.
+    fn cmp_expr<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        binary_level(
+            Self::add_expr,
+            &[Operation::Equal, Operation::LessThan, Operation::GreaterThan],
+        )(input)
+    }
+
+    fn add_expr<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        binary_level(Self::mul_expr, &[Operation::Addition])(input)
+    }
+
+    fn mul_expr<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        binary_level(Self::primary, &[Operation::Multiplication])(input)
+    }
+
+    /// A primary expression, followed by zero or more `(args)` call
+    /// applications - `f(1)(2)` parses as `(f(1))(2)`, and critically this
+    /// applies to any primary, not just a bare identifier: `((x) -> x)(5)`
+    /// and `(2 + 3)(4)` both go through the same suffix.
+    fn primary<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        let (mut expr, mut rest) = Self::primary_atom(input)?;
+
+        while let Some(FullToken { token: Token::LParen, .. }) = rest.first() {
+            let (args, after_args) = delimited(
+                just(Token::LParen),
+                separated_by0(Self::parse, just(Token::Comma)),
+                just(Token::RParen),
+            )(rest)?;
+
+            let span = expr.span().to(consumed_span(rest, after_args));
+            expr = Expression::Call { callee: Box::new(expr), args, span };
+            rest = after_args;
+        }
+
+        Ok((expr, rest))
+    }
+
+    /// A primary expression before any trailing `(args)` calls are applied.
+    /// The leading token unambiguously picks the branch, so this dispatches
+    /// directly rather than trying each one in turn via `or`.
+    fn primary_atom<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        match input.first() {
+            // `(a, b) -> ...` and `(expr)` both start with `(`; try the
+            // lambda reading first since it fails harmlessly (no tokens
+            // consumed) on anything that isn't a param list followed by `->`.
+            Some(FullToken { token: Token::LParen, .. }) => {
+                or(Self::lambda_expr, Self::paren_expr)(input)
+            }
+            Some(FullToken { token: Token::LBrace, .. }) => Self::block(input),
+            Some(FullToken { token: Token::If, .. }) => Self::if_expr(input),
+            Some(FullToken { token: Token::Number(_), .. }) => map_with_span(
+                filter("a number", |t| match t {
+                    Token::Number(n) => Some(*n),
+                    _ => None,
+                }),
+                |n, span| Expression::Term(Term::Value(Value::Number(n)), span),
+            )(input),
+            Some(FullToken { token: Token::String(_), .. }) => map_with_span(
+                filter("a string", |t| match t {
+                    Token::String(s) => Some(s.clone()),
+                    _ => None,
+                }),
+                |s, span| Expression::Term(Term::Value(Value::String(s)), span),
+            )(input),
+            Some(FullToken { token: Token::Identifier(_), .. }) => map_with_span(
+                Self::identifier_name,
+                |name, span| Expression::Term(Term::Variable { name }, span),
+            )(input),
+            Some(full) => Err(InterpError::new(
+                format!("expected an expression, got: {:?}", full.token),
+                full.span,
+            )),
+            None => Err(InterpError::new(
+                "expected an expression, found end of input",
+                eof_span(input),
+            )),
+        }
+    }
+
+    /// `( expression )`.
+    fn paren_expr<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        delimited(just(Token::LParen), Self::parse, just(Token::RParen))(input)
+    }
+
+    /// `(params) -> body`.
+    fn lambda_expr<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        let params = delimited(
+            just(Token::LParen),
+            separated_by0(Self::identifier_name, just(Token::Comma)),
+            just(Token::RParen),
+        );
+
+        // The body parses one tier below the pipe operators (`Self::parse`
+        // is `pipe_expr` itself), not at full expression precedence - a
+        // bare `(n) -> n * 2 |> f` should have the `|>` apply to the
+        // lambda's result, not get folded into its body.
+        map_with_span(
+            then(then(params, just(Token::Arrow)), Self::cmp_expr),
+            |((params, _), body), span| Expression::Lambda { params, body: Box::new(body), span },
+        )(input)
+    }
+
+    fn identifier_name<'t>(input: TokenStream<'t>) -> PResult<'t, String> {
+        filter("an identifier", |t| match t {
+            Token::Identifier(name) => Some(name.clone()),
+            _ => None,
+        })(input)
+    }
+
+    /// The body of a `{ ... }` block: statements, each optionally
+    /// followed by a `;` (optional before the closing `}` too).
+    ///
+    /// The loop is written out by hand rather than through a generic
+    /// zero-or-more combinator, which would discard whatever error its
+    /// inner parser fails with - right for genuinely optional repetition
+    /// but wrong for a statement list: a malformed statement should be a
+    /// hard error, not get silently treated as "no more statements, stop
+    /// before `}`".
+    fn block<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        let (open, mut rest) = just(Token::LBrace)(input)?;
+        let mut statements = Vec::new();
+
+        loop {
+            match rest.first() {
+                Some(FullToken { token: Token::RBrace, .. }) => break,
+                None => return Err(InterpError::new("expected '}', found end of input", open)),
+                _ => {}
+            }
+
+            let (statement, after_statement) = Statement::parse(rest)?;
+            let (_, after_semi) =
+                or(map(just(Token::Semicolon), |_| ()), succeed(()))(after_statement)?;
+
+            statements.push(statement);
+            rest = after_semi;
+        }
+
+        let (close, rest) = just(Token::RBrace)(rest)?;
+
+        Ok((Expression::Block { statements, span: open.to(close) }, rest))
+    }
+
+    /// `if condition { ... } (else ({ ... } | if ...))?`.
+    fn if_expr<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        let (if_span, rest) = just(Token::If)(input)?;
+        let (condition, rest) = Self::parse(rest)?;
+        let (then_branch, rest) = Self::block(rest)?;
+
+        let (else_branch, rest) = match rest.first() {
+            Some(FullToken { token: Token::Else, .. }) => {
+                let (_, rest) = just(Token::Else)(rest)?;
+                let (branch, rest) = or(Self::if_expr, Self::block)(rest)?;
+                (Some(Box::new(branch)), rest)
+            }
+            _ => (None, rest),
+        };
+
+        let span = match &else_branch {
+            Some(branch) => if_span.to(branch.span()),
+            None => if_span.to(then_branch.span()),
+        };
+
+        Ok((
+            Expression::If {
+                condition: Box::new(condition),
+                then_branch: Box::new(then_branch),
+                else_branch,
+                span,
+            },
+            rest,
+        ))
+    }
+
+    pub fn evaluate(&self, env: &Environment, budget: &Budget) -> InterpResult<Outcome> {
+        budget.tick(self.span())?;
+
+        match self {
+            // Evaluate a single term.
+            Expression::Term(term, span) => Ok(Outcome::Value(term.evaluate(env, *span)?)),
+
+            // Evaluate a binary expression. A `return` on either side short-
+            // circuits the whole expression and bubbles straight up.
+            Expression::Binary {
+                left,
+                op,
+                right,
+                span,
+            } => {
+                let left = match left.evaluate(env, budget)? {
+                    Outcome::Return(value) => return Ok(Outcome::Return(value)),
+                    Outcome::Value(value) => value,
+                };
+
+                let right = match right.evaluate(env, budget)? {
+                    Outcome::Return(value) => return Ok(Outcome::Return(value)),
+                    Outcome::Value(value) => value,
+                };
+
+                let result = match op {
+                    Operation::Addition => left.add(right, *span)?,
+                    Operation::Multiplication => left.mul(right, *span)?,
+                    Operation::Equal | Operation::LessThan | Operation::GreaterThan => {
+                        left.compare(right, *op, *span)?
+                    }
+                };
+
+                Ok(Outcome::Value(result))
+            },
+
+            Expression::Block { statements, .. } => {
+                let mut child = Environment::child_of(env);
+                let mut value = Value::Unit;
+
+                for statement in statements {
+                    match statement.evaluate(&mut child, budget)? {
+                        // A `return` inside the block stops it immediately
+                        // and keeps bubbling up to the enclosing call.
+                        Outcome::Return(v) => return Ok(Outcome::Return(v)),
+                        Outcome::Value(v) => value = v,
+                    }
+                }
+
+                Ok(Outcome::Value(value))
+            }
+
+            Expression::Call { callee, args, span } => {
+                let function = match callee.evaluate(env, budget)? {
+                    Outcome::Return(value) => return Ok(Outcome::Return(value)),
+                    Outcome::Value(value) => value,
+                };
+
+                let mut values = Vec::with_capacity(args.len());
+
+                for arg in args {
+                    let value = match arg.evaluate(env, budget)? {
+                        Outcome::Return(value) => return Ok(Outcome::Return(value)),
+                        Outcome::Value(value) => value,
+                    };
+                    values.push(value);
+                }
+
+                // A bare-name callee (the common case, `f(...)`) gets its
+                // name back in arity/type errors; anything else (a lambda
+                // literal, a parenthesized sub-expression...) falls back to
+                // call_value's generic wording.
+                let name = match callee.as_ref() {
+                    Expression::Term(Term::Variable { name }, _) => Some(name.as_str()),
+                    _ => None,
+                };
+
+                Ok(Outcome::Value(call_value(function, values, name, *span, budget)?))
+            }
+
+            Expression::If { condition, then_branch, else_branch, span } => {
+                let condition = match condition.evaluate(env, budget)? {
+                    Outcome::Return(value) => return Ok(Outcome::Return(value)),
+                    Outcome::Value(value) => value,
+                };
+
+                let condition = match condition {
+                    Value::Bool(b) => b,
+                    other => {
+                        return Err(InterpError::new(
+                            format!("if condition must be a Bool, got {}", other.type_name()),
+                            *span,
+                        ));
+                    }
+                };
+
+                if condition {
+                    then_branch.evaluate(env, budget)
+                } else {
+                    match else_branch {
+                        Some(branch) => branch.evaluate(env, budget),
+                        None => Ok(Outcome::Value(Value::Unit)),
+                    }
+                }
+            }
+
+            Expression::Lambda { params, body, .. } => Ok(Outcome::Value(Value::Function {
+                params: params.clone(),
+                body: body.clone(),
+                captured: env.clone(),
+            })),
+
+            // `value |> func` - func(value).
+            Expression::Pipe { value, func, span } => {
+                let value = match value.evaluate(env, budget)? {
+                    Outcome::Return(v) => return Ok(Outcome::Return(v)),
+                    Outcome::Value(v) => v,
+                };
+
+                let function = match func.evaluate(env, budget)? {
+                    Outcome::Return(v) => return Ok(Outcome::Return(v)),
+                    Outcome::Value(v) => v,
+                };
+
+                Ok(Outcome::Value(call_value(function, vec![value], None, *span, budget)?))
+            }
+
+            // `value |: func` - func applied to each element of the List
+            // `value`, collected back into a new List.
+            Expression::PipeMap { value, func, span } => {
+                let items = match value.evaluate(env, budget)? {
+                    Outcome::Return(v) => return Ok(Outcome::Return(v)),
+                    Outcome::Value(Value::List(items)) => items,
+                    Outcome::Value(other) => {
+                        return Err(InterpError::new(
+                            format!("'|:' needs a List on the left, got {}", other.type_name()),
+                            *span,
+                        ));
+                    }
+                };
+
+                let function = match func.evaluate(env, budget)? {
+                    Outcome::Return(v) => return Ok(Outcome::Return(v)),
+                    Outcome::Value(v) => v,
+                };
+
+                let mut out = Vec::with_capacity(items.len());
+                for item in items {
+                    budget.tick(*span)?;
+                    out.push(call_value(function.clone(), vec![item], None, *span, budget)?);
+                }
+
+                Ok(Outcome::Value(Value::List(out)))
+            }
+        }
+    }
+}
+
+/// What evaluating an expression or statement produced: either its plain
+/// value, or a `return` that should keep unwinding until it reaches the
+/// call that's currently executing.
+#[derive(Debug, Clone)]
+enum Outcome {
+    Value(Value),
+    Return(Value),
+}
+
+impl Outcome {
+    fn into_value(self) -> Value {
+        match self {
+            Outcome::Value(value) | Outcome::Return(value) => value,
+        }
+    }
+}
+
+/// A variable scope. Lookups walk up through `parent` when a name isn't
+/// found locally, so a block's bindings don't leak out but can still see
+/// everything the enclosing scope defined.
+#[derive(Debug)]
+struct Environment {
+    variables: HashMap<String, Value>,
+    parent: Option<Box<Environment>>,
+}
+
+impl Environment {
+    /// Create a top-level environment pre-populated with the builtins
+    /// `call_builtin` knows how to dispatch.
+    pub fn new() -> Environment {
+        let mut env = Environment {
+            variables: HashMap::new(),
+            parent: None,
+        };
+
+        for name in ["range", "len", "push", "map", "filter", "foldl"] {
+            env.set(name, Value::Builtin(name));
+        }
+
+        env
+    }
+
+    /// Create a child scope that can see `parent`'s variables but whose own
+    /// bindings don't escape back into it.
+    fn child_of(parent: &Environment) -> Environment {
+        Environment {
+            variables: HashMap::new(),
+            parent: Some(Box::new(parent.clone())),
+        }
+    }
+
+    /// Retrieve a variable's value, walking up the scope chain.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.variables
+            .get(name)
+            .cloned()
+            .or_else(|| self.parent.as_ref().and_then(|parent| parent.get(name)))
+    }
+
+    /// Set a variable's value in this scope.
+    pub fn set(&mut self, name: impl ToString, value: Value) {
+        self.variables.insert(name.to_string(), value);
+    }
+}
+
+impl Clone for Environment {
+    fn clone(&self) -> Environment {
+        Environment {
+            variables: self.variables.clone(),
+            parent: self.parent.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Assignment {
+        name: String,
+        value: Expression,
+    },
+
+    Expression(Expression),
+
+    /// `fn name(params...) { body }`. Stores a `Value::Function` in the
+    /// environment under `name`.
+    FunctionDef {
+        name: String,
+        params: Vec<String>,
+        body: Expression,
+    },
+
+    /// `return expr;`. An early, hard return out of the innermost call.
+    Return(Expression),
+
+    /// `for var : iterable { body }`. `iterable` must evaluate to a
+    /// `Value::List`; `body` runs once per element with `var` bound to it
+    /// in a fresh child scope.
+    For {
+        var: String,
+        iterable: Expression,
+        body: Expression,
+    },
+}
+
+impl Statement {
+    /// Parse a statement. The leading token unambiguously picks the kind,
+    /// the same way `Expression::primary_atom` dispatches.
+    pub fn parse<'t>(input: TokenStream<'t>) -> PResult<'t, Statement> {
+        match input.first() {
+            Some(FullToken { token: Token::Let, .. }) => Self::assignment(input),
+            Some(FullToken { token: Token::Fn, .. }) => Self::function_def(input),
+            Some(FullToken { token: Token::For, .. }) => Self::for_stmt(input),
+            Some(FullToken { token: Token::Return, .. }) => map(
+                then(just(Token::Return), Expression::parse),
+                |(_, expr)| Statement::Return(expr),
+            )(input),
+            Some(_) => map(Expression::parse, Statement::Expression)(input),
+            None => Err(InterpError::new("empty token stream", eof_span(input))),
+        }
+    }
+
+    /// Evaluate the statement given the environment.
+    pub fn evaluate(&self, env: &mut Environment, budget: &Budget) -> InterpResult<Outcome> {
+        match self {
+            Statement::Assignment { name, value } => {
+                let value = match value.evaluate(env, budget)? {
+                    Outcome::Return(value) => return Ok(Outcome::Return(value)),
+                    Outcome::Value(value) => value,
+                };
+                env.set(name, value);
+
+                Ok(Outcome::Value(Value::Unit))
+            }
+
+            Statement::Expression(expression) => expression.evaluate(env, budget),
+
+            Statement::FunctionDef { name, params, body } => {
+                // A named function needs to see itself to recurse. The
+                // captured snapshot can't hold the finished closure (it
+                // would have to contain itself), so it gets a placeholder
+                // under `name`; `call_value` binds `name` to the real
+                // function in each call made by that name.
+                env.set(name, Value::Unit);
+                let captured = env.clone();
+
+                env.set(name, Value::Function {
+                    params: params.clone(),
+                    body: Box::new(body.clone()),
+                    captured,
+                });
+
+                Ok(Outcome::Value(Value::Unit))
+            }
+
+            Statement::Return(expression) => {
+                let value = expression.evaluate(env, budget)?.into_value();
+                Ok(Outcome::Return(value))
+            }
+
+            Statement::For { var, iterable, body } => {
+                let items = match iterable.evaluate(env, budget)? {
+                    Outcome::Return(value) => return Ok(Outcome::Return(value)),
+                    Outcome::Value(Value::List(items)) => items,
+                    Outcome::Value(other) => {
+                        return Err(InterpError::new(
+                            format!("for loop needs a List, got {}", other.type_name()),
+                            iterable.span(),
+                        ));
+                    }
+                };
+
+                let mut value = Value::Unit;
+
+                for item in items {
+                    let mut child = Environment::child_of(env);
+                    child.set(var, item);
+
+                    match body.evaluate(&child, budget)? {
+                        Outcome::Return(v) => return Ok(Outcome::Return(v)),
+                        Outcome::Value(v) => value = v,
+                    }
+                }
+
+                Ok(Outcome::Value(value))
+            }
+        }
+    }
+
+    /// Parse a `fn name(params...) { body }` definition.
+    fn function_def<'t>(input: TokenStream<'t>) -> PResult<'t, Statement> {
+        let params = delimited(
+            just(Token::LParen),
+            separated_by0(Expression::identifier_name, just(Token::Comma)),
+            just(Token::RParen),
+        );
+
+        map(
+            then(then(then(just(Token::Fn), Expression::identifier_name), params), Expression::parse),
+            |(((_, name), params), body)| Statement::FunctionDef { name, params, body },
+        )(input)
+    }
+
+    /// Parse `for var : iterable { body }`.
+    fn for_stmt<'t>(input: TokenStream<'t>) -> PResult<'t, Statement> {
+        map(
+            then(
+                then(
+                    then(just(Token::For), Expression::identifier_name),
+                    then(just(Token::Colon), Expression::parse),
+                ),
+                Expression::block,
+            ),
+            |(((_, var), (_, iterable)), body)| Statement::For { var, iterable, body },
+        )(input)
+    }
+
+    /// Parse `let name = value`.
+    fn assignment<'t>(input: TokenStream<'t>) -> PResult<'t, Statement> {
+        map(
+            then(
+                then(then(just(Token::Let), Expression::identifier_name), just(Token::Equals)),
+                Expression::parse,
+            ),
+            |(((_, name), _), value)| Statement::Assignment { name, value },
+        )(input)
+    }
+}
+
+/// Caps a single `run` so that untrusted source submitted through the web
+/// playground can't hang the server or exhaust memory, e.g. with something
+/// like `fn f(n) { f(n) } f(0)` (a single huge string repeat such as
+/// `"x" * 999999999` is capped separately, by `MAX_STRING_LEN`). Exceeding the step
+/// count, the deadline, or the call-depth limit fails evaluation with a
+/// "computation limit exceeded" error, rendered through the same
+/// diagnostic path as a parse or type error.
+struct Budget {
+    steps_left: Cell<u32>,
+    deadline: Instant,
+    call_depth: Cell<u32>,
+}
+
+/// How deep calls may nest before a recursive program is cut off. Each
+/// level here is a real Rust stack frame, so this needs to stay well under
+/// where the process would otherwise overflow its stack.
+const MAX_CALL_DEPTH: u32 = 200;
+
+impl Budget {
+    fn new() -> Budget {
+        Budget {
+            steps_left: Cell::new(200_000),
+            deadline: Instant::now() + Duration::from_millis(500),
+            call_depth: Cell::new(0),
+        }
+    }
+
+    /// Called once per expression evaluated, and once per element `range`
+    /// produces or `|:` maps over; charges one step and checks the deadline.
+    fn tick(&self, span: Span) -> InterpResult<()> {
+        let steps_left = self.steps_left.get();
+
+        if steps_left == 0 || Instant::now() >= self.deadline {
+            return Err(InterpError::new("computation limit exceeded", span));
+        }
+
+        self.steps_left.set(steps_left - 1);
+        Ok(())
+    }
+
+    /// Charges one level of call nesting, failing once `MAX_CALL_DEPTH` is
+    /// reached rather than letting recursion overflow the real stack.
+    fn enter_call(&self, span: Span) -> InterpResult<()> {
+        let depth = self.call_depth.get();
+
+        if depth >= MAX_CALL_DEPTH {
+            return Err(InterpError::new("computation limit exceeded", span));
+        }
+
+        self.call_depth.set(depth + 1);
+        Ok(())
+    }
+
+    fn exit_call(&self) {
+        self.call_depth.set(self.call_depth.get() - 1);
+    }
+}
+
+/// Everything the playground page shows for a successful run: the token
+/// stream, the parsed statements, and the final value, all pre-rendered as
+/// debug dumps since the template only needs to display them.
+pub struct Report {
+    pub tokens: String,
+    pub ast: String,
+    pub value: String,
+}
+
+/// Lex, parse and evaluate `source` under a step/time/call-depth budget,
+/// for the `/playground` route. On failure, the error is already rendered
+/// against `source` (caret underline and all), ready to drop into HTML.
+pub fn run(source: &str) -> Result<Report, String> {
+    run_inner(source).map_err(|error| error.render(source))
+}
+
+fn run_inner(source: &str) -> InterpResult<Report> {
+    let mut lexer = Lexer::new(source);
+    let tokens = lexer.tokens()?;
+    let tokens_dump = format!("{:#?}", tokens);
+
+    let budget = Budget::new();
+    let mut input: TokenStream = (&tokens[..]).into();
+    let mut env = Environment::new();
+    let mut value = Value::Unit;
+    let mut statements = Vec::new();
+
+    while !input.is_empty() {
+        let (statement, rest) = Statement::parse(input)?;
+        input = rest;
+        statements.push(statement.clone());
+
+        match statement.evaluate(&mut env, &budget)? {
+            Outcome::Return(v) => {
+                value = v;
+                break;
+            }
+            Outcome::Value(v) => value = v,
+        }
+
+        // A `;` separates top-level statements; it's optional at the end.
+        if let Ok((_, rest)) = just(Token::Semicolon)(input) {
+            input = rest;
+        }
+    }
+
+    Ok(Report {
+        tokens: tokens_dump,
+        ast: format!("{:#?}", statements),
+        value: format!("{:?}", value),
+    })
+}
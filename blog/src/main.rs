@@ -1,4 +1,5 @@
 mod controllers;
+mod interpreter;
 mod models;
 
 use std::env::var;
@@ -45,6 +46,7 @@ async fn main() -> Result<(), http::Error> {
         route!("/blog/:page" => controllers::content::Content),
         route!("/blog" => controllers::articles::Articles),
         route!("/rss.xml" => controllers::rss::Rss),
+        route!("/playground" => controllers::playground::Playground),
         route!("/turbo-stream" => TurboStream),
         StaticFiles::serve("static")?,
         NotFound::default().wildcard("/"),
@@ -1,21 +1,82 @@
 #![allow(unused_mut, dead_code, unused_variables)]
-use std::iter::{Peekable, Iterator};
+
+/// A half-open byte range into the source, used to underline the
+/// offending text in diagnostics.
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    start: usize,
+    end: usize,
+}
+
+impl Span {
+    fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    fn to(&self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
 
 /// List of all available tokens in our language.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Number(i64),
     Plus,
+    LParen,
+    RParen,
+}
+
+/// A token together with the span of source it was lexed from.
+#[derive(Debug, Clone)]
+pub struct FullToken {
+    token: Token,
+    span: Span,
 }
 
+/// A lex, parse, or evaluation error, carrying the span of source
+/// responsible so it can be rendered with a caret underline.
+#[derive(Debug)]
+struct InterpError {
+    message: String,
+    span: Span,
+}
+
+impl InterpError {
+    fn new(message: impl Into<String>, span: Span) -> InterpError {
+        InterpError { message: message.into(), span }
+    }
+
+    /// Render the error as the offending source line followed by a
+    /// `^^^` underline beneath the span.
+    fn render(&self, source: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let end = self.span.end.clamp(start, source.len());
+
+        format!(
+            "{}\n{}{} {}",
+            source,
+            " ".repeat(start),
+            "^".repeat((end - start).max(1)),
+            self.message,
+        )
+    }
+}
+
+type InterpResult<T> = Result<T, InterpError>;
+
 /// Lexer takes a string and returns a list of tokens.
 pub struct Lexer<'a> {
     // Source code.
     source: &'a str,
     // Resulting list of tokens.
-    tokens: Vec<Token>,
+    tokens: Vec<FullToken>,
     // Buffer for multi-character tokens.
     buffer: String,
+    // Byte offset where the token currently in `buffer` started.
+    buffer_start: usize,
+    // Current scan position, tracked one character at a time.
+    offset: usize,
 }
 
 impl<'a> Lexer<'a> {
@@ -25,57 +86,299 @@ impl<'a> Lexer<'a> {
             source,
             tokens: Vec::new(),
             buffer: String::new(),
+            buffer_start: 0,
+            offset: 0,
         }
     }
 
     /// Convert code into a list of tokens, consuming the lexer.
-   pub fn tokens(&mut self) -> Vec<Token> {
+    pub fn tokens(&mut self) -> InterpResult<Vec<FullToken>> {
         // Extract tokens one character at a time.
         use Token::*;
-    
+
         for c in self.source.chars() {
+            let start = self.offset;
+
             match c {
                 // Spaces separate tokens.
-                ' ' => self.process_token(),
-                '0'..='9' => self.buffer.push(c),
-                '+' => self.tokens.push(Plus),
-                c => todo!("lexer error, unsupported character: '{}'", c),
+                ' ' => {
+                    self.process_token()?;
+                    self.offset += c.len_utf8();
+                }
+                '0'..='9' => {
+                    if self.buffer.is_empty() {
+                        self.buffer_start = start;
+                    }
+                    self.buffer.push(c);
+                    self.offset += c.len_utf8();
+                }
+                '+' => {
+                    self.process_token()?;
+                    self.offset += c.len_utf8();
+                    self.tokens.push(FullToken { token: Plus, span: Span::new(start, self.offset) });
+                }
+                '(' => {
+                    self.process_token()?;
+                    self.offset += c.len_utf8();
+                    self.tokens.push(FullToken { token: LParen, span: Span::new(start, self.offset) });
+                }
+                ')' => {
+                    self.process_token()?;
+                    self.offset += c.len_utf8();
+                    self.tokens.push(FullToken { token: RParen, span: Span::new(start, self.offset) });
+                }
+                c => {
+                    return Err(InterpError::new(
+                        format!("unsupported character: '{}'", c),
+                        Span::new(start, start + c.len_utf8()),
+                    ));
+                }
             }
         }
-        
+
         // Don't forget to process whatever is in the buffer
         // at the end of the input:
-        self.process_token();
-    
-        std::mem::take(&mut self.tokens)
+        self.process_token()?;
+
+        Ok(std::mem::take(&mut self.tokens))
     }
-    
+
     /// Process a multi-character token stored in the buffer.
-    fn process_token(&mut self) {
+    fn process_token(&mut self) -> InterpResult<()> {
         use Token::*;
 
         // Empty buffer means no more tokens in the input.
         if self.buffer.is_empty() {
-            return;
+            return Ok(());
         }
 
+        let span = Span::new(self.buffer_start, self.buffer_start + self.buffer.len());
+
         // Use the standard library str::parse
         // to convert text to an integer.
-        self.tokens.push(
-            Number(self.buffer.as_str().parse().unwrap())
-        );
+        match self.buffer.as_str().parse() {
+            Ok(number) => self.tokens.push(FullToken { token: Number(number), span }),
+            Err(_) => {
+                let message = format!("invalid number literal '{}'", self.buffer);
+                self.buffer.clear();
+                return Err(InterpError::new(message, span));
+            }
+        }
 
         // Clear the buffer for the next token.
         self.buffer.clear();
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+/// Parser combinators
+///
+/// Instead of `Expression::parse` hand-rolling its own peek/consume/match
+/// control flow, it's built out of a handful of small, reusable pieces: a
+/// combinator is just a function from "what's left to parse" to "what it
+/// produced, and what's left after that". Adding a new piece of grammar is
+/// then a matter of composing existing combinators rather than weaving a
+/// new match arm through the parser.
+
+/// The not-yet-consumed tokens a combinator parses from. Keeps hold of the
+/// full token list (not just what's left) so an "end of input" error can
+/// still point at the last real token instead of nowhere.
+#[derive(Debug, Clone, Copy)]
+struct TokenStream<'t> {
+    all: &'t [FullToken],
+    pos: usize,
+}
+
+impl<'t> TokenStream<'t> {
+    fn first(&self) -> Option<&'t FullToken> {
+        self.all.get(self.pos)
+    }
+
+    /// The stream with its first `n` tokens consumed.
+    fn advance(&self, n: usize) -> TokenStream<'t> {
+        TokenStream { all: self.all, pos: self.pos + n }
+    }
+}
+
+impl<'t> From<&'t [FullToken]> for TokenStream<'t> {
+    fn from(all: &'t [FullToken]) -> TokenStream<'t> {
+        TokenStream { all, pos: 0 }
+    }
+}
+
+/// What every combinator produces: on success, the parsed value and the
+/// tokens left over; on failure, a span-tagged error.
+type PResult<'t, O> = InterpResult<(O, TokenStream<'t>)>;
+
+/// The span to blame when a combinator runs out of tokens: the end of the
+/// last real token, not whatever's left of `input` (which, by the time
+/// anything calls this, is always empty - that's what "ran out" means).
+fn eof_span(input: TokenStream) -> Span {
+    input.all.last().map(|t| Span::new(t.span.end, t.span.end)).unwrap_or(Span::new(0, 0))
+}
+
+/// The span covering everything between `input` (before a combinator ran)
+/// and `rest` (what it left behind).
+fn consumed_span(input: TokenStream, rest: TokenStream) -> Span {
+    let consumed = &input.all[input.pos..rest.pos];
+    match (consumed.first(), consumed.last()) {
+        (Some(first), Some(last)) => first.span.to(last.span),
+        _ => eof_span(input),
+    }
+}
+
+/// Matches a single token equal to `want`.
+fn just<'t>(want: Token) -> impl Fn(TokenStream<'t>) -> PResult<'t, Span> + 't {
+    move |input| match input.first() {
+        Some(full) if full.token == want => Ok((full.span, input.advance(1))),
+        Some(full) => Err(InterpError::new(
+            format!("expected {:?}, got: {:?}", want, full.token),
+            full.span,
+        )),
+        None => Err(InterpError::new(
+            format!("expected {:?}, found end of input", want),
+            eof_span(input),
+        )),
+    }
+}
+
+/// Matches and extracts from a single token, for tokens (`Number`) that
+/// carry data `just` can't compare against.
+fn filter<'t, O>(
+    expected: &'static str,
+    f: impl Fn(&Token) -> Option<O> + 't,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, O> + 't {
+    move |input| match input.first() {
+        Some(full) => match f(&full.token) {
+            Some(out) => Ok((out, input.advance(1))),
+            None => Err(InterpError::new(
+                format!("expected {}, got: {:?}", expected, full.token),
+                full.span,
+            )),
+        },
+        None => Err(InterpError::new(
+            format!("expected {}, found end of input", expected),
+            eof_span(input),
+        )),
+    }
+}
+
+/// Runs `a` then `b` in sequence, pairing up both outputs.
+fn then<'t, A, B>(
+    a: impl Fn(TokenStream<'t>) -> PResult<'t, A> + 't,
+    b: impl Fn(TokenStream<'t>) -> PResult<'t, B> + 't,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, (A, B)> + 't {
+    move |input| {
+        let (a, rest) = a(input)?;
+        let (b, rest) = b(rest)?;
+        Ok(((a, b), rest))
+    }
+}
+
+/// Tries `a`; on failure falls back to `b` against the same input
+/// (combinators never consume anything on a failing run, so there's
+/// nothing to rewind).
+fn or<'t, O>(
+    a: impl Fn(TokenStream<'t>) -> PResult<'t, O> + 't,
+    b: impl Fn(TokenStream<'t>) -> PResult<'t, O> + 't,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, O> + 't {
+    move |input| a(input).or_else(|_| b(input))
+}
+
+/// Zero or more `p`, collected into a `Vec`. Stops, without failing, at
+/// the first run that doesn't match.
+fn repeated<'t, O>(
+    p: impl Fn(TokenStream<'t>) -> PResult<'t, O> + 't,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, Vec<O>> + 't {
+    move |mut input| {
+        let mut out = Vec::new();
+
+        while let Ok((item, rest)) = p(input) {
+            out.push(item);
+            input = rest;
+        }
+
+        Ok((out, input))
+    }
+}
+
+/// One or more `p`, separated by `sep`. `sep`'s own output is discarded.
+fn separated_by<'t, O, S>(
+    p: impl Fn(TokenStream<'t>) -> PResult<'t, O> + 't,
+    sep: impl Fn(TokenStream<'t>) -> PResult<'t, S> + 't,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, Vec<O>> + 't {
+    move |input| {
+        let (first, mut rest) = p(input)?;
+        let mut out = vec![first];
+
+        while let Ok((_, after_sep)) = sep(rest) {
+            let (item, after_item) = p(after_sep)?;
+            out.push(item);
+            rest = after_item;
+        }
+
+        Ok((out, rest))
+    }
+}
+
+/// Transforms a combinator's output, leaving the span/rest plumbing alone.
+fn map<'t, A, B>(
+    p: impl Fn(TokenStream<'t>) -> PResult<'t, A> + 't,
+    f: impl Fn(A) -> B + 't,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, B> + 't {
+    move |input| {
+        let (a, rest) = p(input)?;
+        Ok((f(a), rest))
+    }
+}
+
+/// Like `map`, but `f` also receives the span of everything `p` consumed,
+/// for building AST nodes that carry their own span.
+fn map_with_span<'t, A, B>(
+    p: impl Fn(TokenStream<'t>) -> PResult<'t, A> + 't,
+    f: impl Fn(A, Span) -> B + 't,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, B> + 't {
+    move |input| {
+        let (a, rest) = p(input)?;
+        Ok((f(a, consumed_span(input, rest)), rest))
+    }
+}
+
+/// `p`, preceded by `open` and followed by `close`, keeping only `p`'s
+/// output. Built from `then`/`map` rather than its own primitive.
+fn delimited<'t, O>(
+    open: impl Fn(TokenStream<'t>) -> PResult<'t, Span> + 't,
+    p: impl Fn(TokenStream<'t>) -> PResult<'t, O> + 't,
+    close: impl Fn(TokenStream<'t>) -> PResult<'t, Span> + 't,
+) -> impl Fn(TokenStream<'t>) -> PResult<'t, O> + 't {
+    map(then(then(open, p), close), |((_, out), _)| out)
+}
+
+#[derive(Debug, Clone, Copy)]
 enum Operation {
     /// Addition operation.
     Addition,
 }
 
+impl Operation {
+    /// Every operation's precedence. `Expression::parse` doesn't consult
+    /// it: with `+` the only operation, it folds each operator
+    /// left-to-right at a single level, and every fold builds an
+    /// `Addition` node.
+    fn precedence(&self) -> u8 {
+        match self {
+            Operation::Addition => 1,
+        }
+    }
+
+    fn from_token(token: &Token) -> Option<Operation> {
+        match token {
+            Token::Plus => Some(Operation::Addition),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 enum Value {
     /// A value can be a number. Our language translates
@@ -93,64 +396,87 @@ enum Term {
 #[derive(Debug)]
 enum Expression {
     /// A single term.
-    Term(Term),
+    Term(Term, Span),
 
-    /// A binary operation.
+    /// A binary operation. Both sides are full expressions, not bare
+    /// terms, so `1 + 2 + 3` parses as `(1 + 2) + 3` instead of stopping
+    /// after the first `+`.
     BinaryOp {
         left: Box<Expression>,
         op: Operation,
         right: Box<Expression>,
+        span: Span,
     },
 }
 
 impl Expression {
-    /// Given a stream of tokens, parse a single expression.
-    pub fn parse(
-        stream: &mut Peekable<impl Iterator<Item = Token>>
-    ) -> Expression {
-        let left = Self::term(stream);
-        let op = stream.next();
-
-        match op {
-            Some(op) => {
-                let op = match op {
-                    Token::Plus => Operation::Addition,
-                    _ => panic!("syntax error, expected operator, got: {:?}", op),
-                };
-
-                let right = Expression::term(stream);
-                Expression::BinaryOp {
-                    left: Box::new(left),
-                    op,
-                    right: Box::new(right),
-                }
-            }
-
-            None => left,
+    fn span(&self) -> Span {
+        match self {
+            Expression::Term(_, span) => *span,
+            Expression::BinaryOp { span, .. } => *span,
         }
     }
 
-    /// Given a stream of tokens, parse a single term.
-    fn term(
-        stream: &mut Peekable<impl Iterator<Item = Token>>
-    ) -> Expression {
-        let token = stream.next().expect("parse eof");
+    /// Parse a single expression: a `+`-chain of primaries, left-folded
+    /// into `BinaryOp` nodes so `1 + 2 + 3` parses as `(1 + 2) + 3`.
+    /// Doesn't itself check for end-of-input; `run` does that once the
+    /// whole program has been consumed.
+    pub fn parse<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        let (first, rest) = Self::primary(input)?;
 
-        match token {
-            Token::Number(n) => {
-                Expression::Term(Term::Value(Value::Number(n)))
+        let (pairs, rest) = repeated(|i| {
+            let (_, after_op) = filter("an operator", |t| Operation::from_token(t))(i)?;
+            let (right, after_right) = Self::primary(after_op)?;
+            Ok((right, after_right))
+        })(rest)?;
+
+        let expr = pairs.into_iter().fold(first, |left, right| {
+            let span = left.span().to(right.span());
+            Expression::BinaryOp {
+                left: Box::new(left),
+                op: Operation::Addition,
+                right: Box::new(right),
+                span,
             }
+        });
 
-            _ => panic!("syntax error, expected term, got: {:?}", token),
-        }
+        Ok((expr, rest))
+    }
+
+    /// Parse a primary expression: a term, or a parenthesized sub-expression.
+    fn primary<'t>(input: TokenStream<'t>) -> PResult<'t, Expression> {
+        or(
+            map_with_span(
+                filter("a number", |t| match t {
+                    Token::Number(n) => Some(*n),
+                    _ => None,
+                }),
+                |n, span| Expression::Term(Term::Value(Value::Number(n)), span),
+            ),
+            delimited(just(Token::LParen), Self::parse, just(Token::RParen)),
+        )(input)
     }
 }
 
 fn main () {
-    let source = "21 + 2";
+    let source = "21 + 2 + (4 + 5)";
+
+    match run(source) {
+        Ok(expr) => println!("{:?}", expr),
+        Err(error) => println!("{}", error.render(source)),
+    }
+}
+
+fn run(source: &str) -> InterpResult<Expression> {
     let mut lexer = Lexer::new(source);
-    println!("{:?}", lexer.tokens());
-    println!("{:?}", Expression::parse(
-        &mut lexer.tokens().into_iter().peekable()
-    ));
-}
\ No newline at end of file
+    let tokens = lexer.tokens()?;
+    let (expr, rest) = Expression::parse((&tokens[..]).into())?;
+
+    match rest.first() {
+        Some(full) => Err(InterpError::new(
+            format!("expected end of input, got: {:?}", full.token),
+            full.span,
+        )),
+        None => Ok(expr),
+    }
+}